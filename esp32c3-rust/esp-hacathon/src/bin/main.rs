@@ -6,14 +6,51 @@
     holding buffers for the duration of a data transfer."
 )]
 
+#[cfg(any(feature = "promiscuous", feature = "csi", feature = "network"))]
+use core::cell::RefCell;
+use core::fmt::Write;
+#[cfg(any(feature = "promiscuous", feature = "csi", feature = "network"))]
+use critical_section::Mutex;
+#[cfg(feature = "display")]
+use embedded_graphics::mono_font::ascii::FONT_6X10;
+#[cfg(feature = "display")]
+use embedded_graphics::mono_font::MonoTextStyle;
+#[cfg(feature = "display")]
+use embedded_graphics::pixelcolor::BinaryColor;
+#[cfg(feature = "display")]
+use embedded_graphics::prelude::*;
+#[cfg(feature = "display")]
+use embedded_graphics::text::Text;
 use esp_hal::clock::CpuClock;
+#[cfg(feature = "display")]
+use esp_hal::delay::Delay;
+#[cfg(feature = "display")]
+use esp_hal::gpio::{Input, InputConfig, Level, Output, OutputConfig};
+#[cfg(feature = "network")]
+use esp_hal::interrupt::Priority;
 use esp_hal::main;
+#[cfg(feature = "display")]
+use esp_hal::spi::master::Spi;
 use esp_hal::time::{Duration, Instant};
 use esp_hal::timer::timg::TimerGroup;
 use esp_hal::uart::Uart;
 use esp_radio::ble::controller::BleConnector;
+#[cfg(feature = "csi")]
+use esp_radio::wifi::csi::{CsiConfig, WifiCsiInfo};
+#[cfg(feature = "network")]
+use esp_radio::wifi::sta::StationConfig;
+#[cfg(feature = "network")]
+use esp_radio::wifi::Config as WifiConfig;
+#[cfg(feature = "promiscuous")]
+use esp_radio::wifi::PromiscuousPkt;
 use esp_radio::wifi::ScanConfig;
-use core::fmt::Write;
+#[cfg(feature = "promiscuous")]
+use heapless::FnvIndexMap;
+use libm::sqrtf;
+#[cfg(feature = "display")]
+use ssd1680::driver::Ssd1680;
+#[cfg(feature = "display")]
+use ssd1680::graphics::{Display, Display2in13, DisplayRotation};
 
 #[panic_handler]
 fn panic(_: &core::panic::PanicInfo) -> ! {
@@ -26,6 +63,694 @@ extern crate alloc;
 // For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
 esp_bootloader_esp_idf::esp_app_desc!();
 
+/// How often we kick off a passive BLE advertisement scan. Slower than the
+/// WiFi scan since we only need to notice devices entering/leaving the room,
+/// not fine-grained multipath changes.
+const BLE_SCAN_INTERVAL: Duration = Duration::from_millis(2000);
+const BLE_SCAN_WINDOW: Duration = Duration::from_millis(200);
+
+/// A BLE advertiser we've seen before, tracked by a smoothed (EMA) RSSI so a
+/// single noisy reading doesn't trigger a false baseline update.
+#[derive(Clone, Copy)]
+struct BleDevice {
+    addr: [u8; 6],
+    rssi_ema: i8,
+}
+
+// `BleConnector` only exposes the raw blocking HCI transport (`read`/`write`),
+// not a scan API, so the passive scan is implemented directly against it
+// here, the same way a full host stack (bleps/trouble-host) would, just
+// pared down to the handful of commands/events a passive scan needs.
+const HCI_COMMAND_PACKET: u8 = 0x01;
+const HCI_EVENT_PACKET: u8 = 0x04;
+const HCI_EVENT_LE_META: u8 = 0x3e;
+const HCI_SUBEVENT_LE_ADVERTISING_REPORT: u8 = 0x02;
+const OGF_LE_CONTROLLER: u16 = 0x08;
+const OCF_LE_SET_SCAN_PARAMETERS: u16 = 0x000b;
+const OCF_LE_SET_SCAN_ENABLE: u16 = 0x000c;
+
+fn hci_opcode(ocf: u16) -> u16 {
+    (OGF_LE_CONTROLLER << 10) | ocf
+}
+
+fn hci_send_command(connector: &mut BleConnector<'_>, ocf: u16, params: &[u8]) {
+    let mut packet: heapless::Vec<u8, 16> = heapless::Vec::new();
+    let _ = packet.push(HCI_COMMAND_PACKET);
+    let _ = packet.extend_from_slice(&hci_opcode(ocf).to_le_bytes());
+    let _ = packet.push(params.len() as u8);
+    let _ = packet.extend_from_slice(params);
+    let _ = connector.write(&packet);
+}
+
+/// Pulls one complete HCI event packet off the front of `rx`, if one has
+/// fully arrived, and removes it from the buffer.
+fn hci_take_event(rx: &mut heapless::Vec<u8, 256>) -> Option<(u8, heapless::Vec<u8, 255>)> {
+    if rx.len() < 3 || rx[0] != HCI_EVENT_PACKET {
+        return None;
+    }
+    let event_code = rx[1];
+    let param_len = rx[2] as usize;
+    let consumed = 3 + param_len;
+    if rx.len() < consumed {
+        return None;
+    }
+
+    let mut params: heapless::Vec<u8, 255> = heapless::Vec::new();
+    let _ = params.extend_from_slice(&rx[3..consumed]);
+    rx.copy_within(consumed.., 0);
+    rx.truncate(rx.len() - consumed);
+    Some((event_code, params))
+}
+
+/// Parses an LE Advertising Report sub-event and appends each report's
+/// (address, RSSI) to `out`, skipping addresses already seen this scan.
+fn parse_le_advertising_reports(params: &[u8], out: &mut heapless::Vec<([u8; 6], i8), 8>) {
+    if params.len() < 2 || params[0] != HCI_SUBEVENT_LE_ADVERTISING_REPORT {
+        return;
+    }
+    let num_reports = params[1] as usize;
+    let mut offset = 2;
+
+    for _ in 0..num_reports {
+        // event_type(1) + address_type(1) + address(6) + data_len(1)
+        if params.len() < offset + 9 {
+            return;
+        }
+        let mut addr = [0u8; 6];
+        addr.copy_from_slice(&params[offset + 2..offset + 8]);
+        let data_len = params[offset + 8] as usize;
+        let rssi_idx = offset + 9 + data_len;
+        if params.len() <= rssi_idx {
+            return;
+        }
+        let rssi = params[rssi_idx] as i8;
+
+        if out.iter().all(|(a, _)| *a != addr) {
+            let _ = out.push((addr, rssi));
+        }
+
+        offset = rssi_idx + 1;
+    }
+}
+
+/// Runs a passive BLE scan for `window`, returning up to 8 unique
+/// advertiser addresses with the first RSSI reported for each this scan.
+fn ble_scan_advertisements(
+    connector: &mut BleConnector<'_>,
+    window: Duration,
+) -> heapless::Vec<([u8; 6], i8), 8> {
+    // Passive scan (no SCAN_REQ), ~10ms interval/window, public own address,
+    // no advertiser filtering.
+    hci_send_command(
+        connector,
+        OCF_LE_SET_SCAN_PARAMETERS,
+        &[0x00, 0x10, 0x00, 0x10, 0x00, 0x00, 0x00],
+    );
+    hci_send_command(connector, OCF_LE_SET_SCAN_ENABLE, &[0x01, 0x00]);
+
+    let mut reports: heapless::Vec<([u8; 6], i8), 8> = heapless::Vec::new();
+    let mut rx: heapless::Vec<u8, 256> = heapless::Vec::new();
+    let mut scratch = [0u8; 64];
+    let start = Instant::now();
+
+    while start.elapsed() < window && !reports.is_full() {
+        if let Ok(n) = connector.read(&mut scratch) {
+            if n > 0 {
+                let _ = rx.extend_from_slice(&scratch[..n]);
+            }
+        }
+
+        while let Some((event_code, params)) = hci_take_event(&mut rx) {
+            if event_code == HCI_EVENT_LE_META {
+                parse_le_advertising_reports(&params, &mut reports);
+            }
+        }
+    }
+
+    hci_send_command(connector, OCF_LE_SET_SCAN_ENABLE, &[0x00, 0x00]);
+    reports
+}
+
+/// Sliding window of RSSI samples per AP, with count/mean/M2 maintained
+/// online via Welford's algorithm so `mean` and `variance` never require
+/// re-scanning the window. `ring` is what makes it a *sliding* window:
+/// once full, the oldest sample is removed from the Welford aggregate
+/// before the new one is added, so statistics reflect only the last
+/// `AP_WINDOW` samples rather than the AP's entire lifetime.
+const AP_WINDOW: usize = 30;
+/// An AP is flagged "disturbed" once its current reading is more than this
+/// many standard deviations from its running mean.
+const AP_Z_THRESHOLD: f32 = 3.0;
+/// Below this sigma a link is considered too quiet/stable for z-scoring to
+/// be meaningful.
+const AP_NOISE_FLOOR: f32 = 1.5;
+/// Motion is only reported once at least this many tracked APs are
+/// disturbed at the same time, to reject single-AP transient noise.
+const AP_MIN_DISTURBED: usize = 2;
+
+struct ApStats {
+    ssid: heapless::String<32>,
+    ring: [i8; AP_WINDOW],
+    write_idx: usize,
+    count: u32,
+    mean: f32,
+    m2: f32,
+}
+
+impl ApStats {
+    fn new(ssid: heapless::String<32>, rssi: i8) -> Self {
+        let mut stats = ApStats {
+            ssid,
+            ring: [0; AP_WINDOW],
+            write_idx: 0,
+            count: 0,
+            mean: 0.0,
+            m2: 0.0,
+        };
+        stats.push(rssi);
+        stats
+    }
+
+    fn push(&mut self, rssi: i8) {
+        if self.count as usize == AP_WINDOW {
+            let oldest = self.ring[self.write_idx];
+            welford_remove(&mut self.count, &mut self.mean, &mut self.m2, oldest as f32);
+        }
+
+        self.ring[self.write_idx] = rssi;
+        self.write_idx = (self.write_idx + 1) % AP_WINDOW;
+        welford_add(&mut self.count, &mut self.mean, &mut self.m2, rssi as f32);
+    }
+
+    fn sigma(&self) -> f32 {
+        if self.count > 1 {
+            sqrtf(self.m2 / (self.count - 1) as f32)
+        } else {
+            0.0
+        }
+    }
+
+    /// `|current - mean| > k*sigma`, gated on the window being full (warm-up)
+    /// and on sigma clearing the noise floor so a perfectly stable link never
+    /// trips on rounding noise.
+    fn is_disturbed(&self, current: i8) -> bool {
+        if (self.count as usize) < AP_WINDOW {
+            return false;
+        }
+        let sigma = self.sigma();
+        sigma > AP_NOISE_FLOOR && (current as f32 - self.mean).abs() > AP_Z_THRESHOLD * sigma
+    }
+}
+
+/// Where a scanned SSID's `ApStats` lives (or should go) in the tracking
+/// array.
+enum ApSlot {
+    /// Index of the existing entry for this SSID.
+    Existing(usize),
+    /// Index of a free entry a new SSID can be allocated into.
+    Free(usize),
+    /// No existing entry and no free slot.
+    Full,
+}
+
+/// Finds the tracking slot for `ssid`, by identity rather than by this
+/// scan's sort position: scan order reshuffles precisely when an AP's RSSI
+/// is disturbed, so a position-keyed free-slot search would let a still-
+/// occupied slot permanently block a genuinely new SSID from ever being
+/// tracked.
+fn find_ap_slot<const N: usize>(ap_stats: &[Option<ApStats>; N], ssid: &str) -> ApSlot {
+    if let Some(idx) = ap_stats
+        .iter()
+        .position(|stats| matches!(stats, Some(s) if s.ssid.as_str() == ssid))
+    {
+        return ApSlot::Existing(idx);
+    }
+
+    match ap_stats.iter().position(|stats| stats.is_none()) {
+        Some(idx) => ApSlot::Free(idx),
+        None => ApSlot::Full,
+    }
+}
+
+#[cfg(test)]
+mod ap_slot_tests {
+    use super::*;
+
+    #[test]
+    fn new_ssid_gets_free_slot_even_when_scan_order_reshuffles() {
+        let mut ap_stats: [Option<ApStats>; 3] = [const { None }; 3];
+        ap_stats[0] = Some(ApStats::new(
+            heapless::String::try_from("existing").unwrap(),
+            -40,
+        ));
+        // Slot 1, the position this scan sorted the new SSID into, is
+        // occupied by a *different*, still-tracked AP that simply isn't
+        // sorted there this round -- exactly the reshuffle this redesign
+        // exists to survive.
+        ap_stats[1] = Some(ApStats::new(
+            heapless::String::try_from("other").unwrap(),
+            -60,
+        ));
+
+        match find_ap_slot(&ap_stats, "brand-new") {
+            ApSlot::Free(idx) => assert_eq!(idx, 2),
+            _ => panic!("expected the first genuinely free slot to be found"),
+        }
+    }
+
+    #[test]
+    fn already_tracked_ssid_is_found_by_identity() {
+        let mut ap_stats: [Option<ApStats>; 3] = [const { None }; 3];
+        ap_stats[0] = Some(ApStats::new(
+            heapless::String::try_from("other").unwrap(),
+            -60,
+        ));
+        ap_stats[1] = Some(ApStats::new(
+            heapless::String::try_from("tracked").unwrap(),
+            -40,
+        ));
+
+        match find_ap_slot(&ap_stats, "tracked") {
+            ApSlot::Existing(idx) => assert_eq!(idx, 1),
+            _ => panic!("expected the existing slot to be found"),
+        }
+    }
+
+    #[test]
+    fn full_table_reports_full() {
+        let mut ap_stats: [Option<ApStats>; 2] = [const { None }; 2];
+        ap_stats[0] = Some(ApStats::new(heapless::String::try_from("a").unwrap(), -40));
+        ap_stats[1] = Some(ApStats::new(heapless::String::try_from("b").unwrap(), -40));
+
+        assert!(matches!(find_ap_slot(&ap_stats, "c"), ApSlot::Full));
+    }
+}
+
+fn welford_add(count: &mut u32, mean: &mut f32, m2: &mut f32, value: f32) {
+    *count += 1;
+    let delta = value - *mean;
+    *mean += delta / *count as f32;
+    let delta2 = value - *mean;
+    *m2 += delta * delta2;
+}
+
+fn welford_remove(count: &mut u32, mean: &mut f32, m2: &mut f32, value: f32) {
+    if *count <= 1 {
+        *count = 0;
+        *mean = 0.0;
+        *m2 = 0.0;
+        return;
+    }
+
+    let n = *count as f32;
+    let old_mean = (*mean * n - value) / (n - 1.0);
+    *m2 -= (value - *mean) * (value - old_mean);
+    *mean = old_mean;
+    *count -= 1;
+}
+
+/// Source MAC + metadata pulled out of a raw 802.11 frame by the
+/// promiscuous-mode receive callback. Kept tiny since it has to fit through
+/// a bounded queue out of interrupt context.
+#[cfg(feature = "promiscuous")]
+#[derive(Clone, Copy)]
+struct SniffedFrame {
+    addr2: [u8; 6],
+    rssi: i8,
+}
+
+/// A unique transmitter seen while sniffing, expired if it goes quiet for
+/// longer than `STATION_EXPIRY`.
+#[cfg(feature = "promiscuous")]
+struct Station {
+    last_seen: Instant,
+    rssi_ema: i8,
+    packet_count: u32,
+}
+
+#[cfg(feature = "promiscuous")]
+const STATION_EXPIRY: Duration = Duration::from_secs(30);
+
+#[cfg(feature = "promiscuous")]
+static SNIFFED_FRAMES: Mutex<RefCell<heapless::Deque<SniffedFrame, 32>>> =
+    Mutex::new(RefCell::new(heapless::Deque::new()));
+
+/// Frame Control field lives in the first two bytes of the MAC header;
+/// type is bits 2-3, subtype is bits 4-7. Address 2 (transmitter address)
+/// starts at byte 10 for both data and management frames.
+#[cfg(feature = "promiscuous")]
+fn parse_source_mac(buf: &[u8]) -> Option<[u8; 6]> {
+    const TYPE_MANAGEMENT: u8 = 0b00;
+    const TYPE_DATA: u8 = 0b10;
+    const SUBTYPE_PROBE_REQUEST: u8 = 0b0100;
+
+    if buf.len() < 16 {
+        return None;
+    }
+
+    let frame_control = u16::from_le_bytes([buf[0], buf[1]]);
+    let frame_type = ((frame_control >> 2) & 0b11) as u8;
+    let frame_subtype = ((frame_control >> 4) & 0b1111) as u8;
+
+    let is_relevant = frame_type == TYPE_DATA
+        || (frame_type == TYPE_MANAGEMENT && frame_subtype == SUBTYPE_PROBE_REQUEST);
+    if !is_relevant {
+        return None;
+    }
+
+    let mut addr2 = [0u8; 6];
+    addr2.copy_from_slice(&buf[10..16]);
+    Some(addr2)
+}
+
+#[cfg(feature = "promiscuous")]
+fn on_promiscuous_frame(pkt: PromiscuousPkt<'_>) {
+    let Some(addr2) = parse_source_mac(pkt.data) else {
+        return;
+    };
+    let frame = SniffedFrame {
+        addr2,
+        rssi: pkt.rx_cntl.rssi as i8,
+    };
+
+    critical_section::with(|cs| {
+        let mut queue = SNIFFED_FRAMES.borrow_ref_mut(cs);
+        if queue.is_full() {
+            queue.pop_front();
+        }
+        let _ = queue.push_back(frame);
+    });
+}
+
+#[cfg(feature = "promiscuous")]
+fn update_station(
+    stations: &mut FnvIndexMap<[u8; 6], Station, 32>,
+    frame: SniffedFrame,
+    now: Instant,
+) {
+    match stations.get_mut(&frame.addr2) {
+        Some(station) => {
+            station.rssi_ema =
+                ((station.rssi_ema as i16 * 80 + frame.rssi as i16 * 20) / 100) as i8;
+            station.last_seen = now;
+            station.packet_count = station.packet_count.saturating_add(1);
+        }
+        None => {
+            let _ = stations.insert(
+                frame.addr2,
+                Station {
+                    last_seen: now,
+                    rssi_ema: frame.rssi,
+                    packet_count: 1,
+                },
+            );
+        }
+    }
+}
+
+/// Per-subcarrier amplitude history used to compute "motion energy": human
+/// movement perturbs multipath far more than it perturbs scalar RSSI, so the
+/// variance of each subcarrier's amplitude over time is a much more
+/// sensitive motion signal than the per-AP RSSI z-score it complements.
+#[cfg(feature = "csi")]
+const CSI_SUBCARRIERS: usize = 52;
+#[cfg(feature = "csi")]
+const CSI_GUARD_OFFSET: usize = 6;
+#[cfg(feature = "csi")]
+const CSI_RING_LEN: usize = 64;
+#[cfg(feature = "csi")]
+const CSI_MOTION_K: f32 = 2.5;
+#[cfg(feature = "csi")]
+const CSI_BASELINE_ALPHA: f32 = 0.1;
+
+#[cfg(feature = "csi")]
+struct CsiRingBuffers {
+    amplitude: [[u16; CSI_RING_LEN]; CSI_SUBCARRIERS],
+    write_idx: usize,
+    filled: bool,
+}
+
+#[cfg(feature = "csi")]
+static CSI_BUFFERS: Mutex<RefCell<CsiRingBuffers>> = Mutex::new(RefCell::new(CsiRingBuffers {
+    amplitude: [[0; CSI_RING_LEN]; CSI_SUBCARRIERS],
+    write_idx: 0,
+    filled: false,
+}));
+
+/// CSI receive callback: pulls the per-subcarrier (I, Q) pairs out of the
+/// raw channel estimate, skipping the guard/null subcarriers at the edges,
+/// and records each subcarrier's amplitude into its ring buffer slot.
+#[cfg(feature = "csi")]
+fn on_csi_frame(csi: WifiCsiInfo<'_>) {
+    critical_section::with(|cs| {
+        let mut buffers = CSI_BUFFERS.borrow_ref_mut(cs);
+        let write_idx = buffers.write_idx;
+        let buf = csi.buf();
+
+        for subcarrier in 0..CSI_SUBCARRIERS {
+            let raw_idx = CSI_GUARD_OFFSET + subcarrier;
+            let Some(&i) = buf.get(raw_idx * 2) else {
+                break;
+            };
+            let Some(&q) = buf.get(raw_idx * 2 + 1) else {
+                break;
+            };
+
+            let amplitude = sqrtf((i as f32) * (i as f32) + (q as f32) * (q as f32));
+            buffers.amplitude[subcarrier][write_idx] = amplitude as u16;
+        }
+
+        buffers.write_idx = (write_idx + 1) % CSI_RING_LEN;
+        if buffers.write_idx == 0 {
+            buffers.filled = true;
+        }
+    });
+}
+
+/// Mean, across all subcarriers, of each subcarrier's temporal variance over
+/// its ring buffer. Returns `None` until the buffers have filled once.
+#[cfg(feature = "csi")]
+fn csi_motion_energy() -> Option<f32> {
+    critical_section::with(|cs| {
+        let buffers = CSI_BUFFERS.borrow_ref(cs);
+        if !buffers.filled {
+            return None;
+        }
+
+        let mut total_variance = 0.0f32;
+        for samples in buffers.amplitude.iter() {
+            let mean = samples.iter().map(|&s| s as f32).sum::<f32>() / CSI_RING_LEN as f32;
+            let variance = samples
+                .iter()
+                .map(|&s| {
+                    let diff = s as f32 - mean;
+                    diff * diff
+                })
+                .sum::<f32>()
+                / CSI_RING_LEN as f32;
+            total_variance += variance;
+        }
+
+        Some(total_variance / CSI_SUBCARRIERS as f32)
+    })
+}
+
+// WiFi credentials and broker address are baked in at compile time rather
+// than read from flash/NVS, matching how this hackathon build already
+// expects `cfg`-gated features to be configured per flash.
+#[cfg(feature = "network")]
+const WIFI_SSID: &str = env!("WIFI_SSID");
+#[cfg(feature = "network")]
+const WIFI_PASSWORD: &str = env!("WIFI_PASSWORD");
+#[cfg(feature = "network")]
+const MQTT_BROKER_IP: &str = env!("MQTT_BROKER_IP");
+#[cfg(feature = "network")]
+const MQTT_TOPIC: &str = "esp32/motion";
+#[cfg(feature = "network")]
+const SNTP_SERVER: &str = "pool.ntp.org";
+#[cfg(feature = "network")]
+const NTP_UNIX_EPOCH_DELTA: u32 = 2_208_988_800; // seconds between 1900-01-01 and 1970-01-01
+
+/// JSON records produced by the scan loop, waiting to be published. Bounded
+/// the same way the sniffer/BLE state is: the network task may be mid
+/// reconnect, so a handful of records can back up without blocking sensing.
+#[cfg(feature = "network")]
+static TELEMETRY_QUEUE: Mutex<RefCell<heapless::Deque<heapless::String<512>, 8>>> =
+    Mutex::new(RefCell::new(heapless::Deque::new()));
+
+#[cfg(feature = "network")]
+fn enqueue_telemetry(line: heapless::String<512>) {
+    critical_section::with(|cs| {
+        let mut queue = TELEMETRY_QUEUE.borrow_ref_mut(cs);
+        if queue.is_full() {
+            queue.pop_front();
+        }
+        let _ = queue.push_back(line);
+    });
+}
+
+#[cfg(feature = "network")]
+fn dequeue_telemetry() -> Option<heapless::String<512>> {
+    critical_section::with(|cs| TELEMETRY_QUEUE.borrow_ref_mut(cs).pop_front())
+}
+
+/// One-shot SNTP client: sends a minimal NTPv3 client request (mode 3) and
+/// reads the epoch seconds back out of the transmit timestamp field. Good
+/// enough to stamp telemetry; we don't need sub-second precision or
+/// periodic resync for a presence sensor.
+#[cfg(feature = "network")]
+async fn sync_sntp_time(stack: embassy_net::Stack<'static>) -> Option<u64> {
+    let mut rx_meta = [embassy_net::udp::PacketMetadata::EMPTY; 4];
+    let mut tx_meta = [embassy_net::udp::PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0u8; 128];
+    let mut tx_buffer = [0u8; 128];
+    let mut socket = embassy_net::udp::UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(0).ok()?;
+
+    let server_addr = stack
+        .dns_query(SNTP_SERVER, embassy_net::dns::DnsQueryType::A)
+        .await
+        .ok()?
+        .first()
+        .copied()?;
+
+    let mut request = [0u8; 48];
+    request[0] = 0b00_011_011; // LI=0, VN=3, Mode=3 (client)
+    socket.send_to(&request, (server_addr, 123)).await.ok()?;
+
+    let mut response = [0u8; 48];
+    let (n, _) = socket.recv_from(&mut response).await.ok()?;
+    if n < 48 {
+        return None;
+    }
+
+    let ntp_seconds = u32::from_be_bytes(response[40..44].try_into().ok()?);
+    Some(ntp_seconds.saturating_sub(NTP_UNIX_EPOCH_DELTA) as u64)
+}
+
+/// Owns the MQTT connection: syncs wall-clock time once at boot, then drains
+/// `TELEMETRY_QUEUE` and publishes each record, stamped with the current
+/// epoch time, to `MQTT_TOPIC`. Reconnects to the broker if the connection
+/// drops instead of giving up, since this is meant to run headless.
+#[cfg(feature = "network")]
+#[embassy_executor::task]
+async fn network_task(stack: embassy_net::Stack<'static>) {
+    stack.wait_config_up().await;
+
+    let boot_epoch = sync_sntp_time(stack).await.unwrap_or(0);
+    let boot_instant = Instant::now();
+
+    let mut rx_buffer = [0u8; 1024];
+    let mut tx_buffer = [0u8; 1024];
+
+    let Ok(broker_ip) = MQTT_BROKER_IP.parse::<core::net::Ipv4Addr>() else {
+        return;
+    };
+
+    loop {
+        let mut socket = embassy_net::tcp::TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        if socket.connect((broker_ip, 1883)).await.is_err() {
+            embassy_time::Timer::after_secs(5).await;
+            continue;
+        }
+
+        let mqtt_config = rust_mqtt::client::client_config::ClientConfig::new(
+            rust_mqtt::client::client_config::MqttVersion::MQTTv5,
+            rust_mqtt::utils::rng_generator::CountingRng(0),
+        );
+        let mut recv_buffer = [0u8; 256];
+        let mut write_buffer = [0u8; 256];
+        let mut mqtt_client = rust_mqtt::client::client::MqttClient::<_, 5, _>::new(
+            socket,
+            &mut write_buffer,
+            256,
+            &mut recv_buffer,
+            256,
+            mqtt_config,
+        );
+        if mqtt_client.connect_to_broker().await.is_err() {
+            embassy_time::Timer::after_secs(5).await;
+            continue;
+        }
+
+        loop {
+            let Some(record) = dequeue_telemetry() else {
+                embassy_time::Timer::after_millis(100).await;
+                continue;
+            };
+
+            let epoch_now = boot_epoch + boot_instant.elapsed().as_secs();
+            let mut stamped: heapless::String<560> = heapless::String::new();
+            let _ = write!(stamped, "{{\"ts\":{},\"data\":{}}}", epoch_now, record);
+
+            if mqtt_client
+                .send_message(
+                    MQTT_TOPIC,
+                    stamped.as_bytes(),
+                    rust_mqtt::packet::v5::publish_packet::QualityOfService::QoS0,
+                    false,
+                )
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+}
+
+/// Drives the embassy-net interface: must stay polled for DHCP, DNS and the
+/// sockets `network_task` opens to make any progress.
+#[cfg(feature = "network")]
+#[embassy_executor::task]
+async fn net_runner_task(
+    mut runner: embassy_net::Runner<'static, esp_radio::wifi::Interface<'static>>,
+) {
+    runner.run().await
+}
+
+/// Render the same per-scan data that feeds the UART JSON line onto the
+/// on-device e-paper: motion state + presence score, active AP count, and
+/// the strongest few SSIDs with their RSSI. Kept to a handful of text lines
+/// since a 2.13" panel has no room for more.
+#[cfg(feature = "display")]
+fn render_display(
+    display: &mut Display2in13,
+    motion_detected: bool,
+    presence: f32,
+    ap_count: usize,
+    top_aps: &[(&str, i8, u8, f32, f32, bool)],
+) {
+    let _ = display.clear(BinaryColor::Off);
+    let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+
+    let mut status_line: heapless::String<32> = heapless::String::new();
+    let _ = write!(
+        status_line,
+        "{} p={:.2}",
+        if motion_detected { "MOTION" } else { "still" },
+        presence
+    );
+    let _ = Text::new(&status_line, Point::new(0, 10), style).draw(display);
+
+    let mut count_line: heapless::String<32> = heapless::String::new();
+    let _ = write!(count_line, "APs: {}", ap_count);
+    let _ = Text::new(&count_line, Point::new(0, 22), style).draw(display);
+
+    for (idx, (ssid, rssi, ..)) in top_aps.iter().take(3).enumerate() {
+        let mut line: heapless::String<32> = heapless::String::new();
+        let _ = write!(line, "{} {}", ssid, rssi);
+        let y = 34 + idx as i32 * 12;
+        let _ = Text::new(&line, Point::new(0, y), style).draw(display);
+    }
+}
+
 #[main]
 fn main() -> ! {
     // generator version: 1.0.1
@@ -42,29 +767,200 @@ fn main() -> ! {
         esp_hal::interrupt::software::SoftwareInterruptControl::new(peripherals.SW_INTERRUPT);
     esp_rtos::start(timg0.timer0, sw_interrupt.software_interrupt0);
     let radio_init = esp_radio::init().expect("Failed to initialize Wi-Fi/BLE controller");
-    let (mut wifi_controller, _interfaces) =
+    #[cfg_attr(
+        not(any(feature = "promiscuous", feature = "network")),
+        allow(unused_variables)
+    )]
+    let (mut wifi_controller, interfaces) =
         esp_radio::wifi::new(&radio_init, peripherals.WIFI, Default::default())
             .expect("Failed to initialize Wi-Fi controller");
-    let _connector = BleConnector::new(&radio_init, peripherals.BT, Default::default());
+    let mut ble_connector = BleConnector::new(peripherals.BT, Default::default())
+        .expect("Failed to initialize BLE controller");
 
     // Initialize UART for USB serial communication
     let mut uart0 = Uart::new(peripherals.UART0, Default::default()).unwrap();
 
+    // Optional on-device status display so the sensor is readable without
+    // the host TUI attached. SPI wiring mirrors the Wokwi SSD1680 example.
+    #[cfg(feature = "display")]
+    let mut epd_delay = Delay::new();
+    #[cfg(feature = "display")]
+    let epd_spi_bus = Spi::new(peripherals.SPI2, Default::default())
+        .expect("Failed to initialize e-paper SPI")
+        .with_sck(peripherals.GPIO6)
+        .with_mosi(peripherals.GPIO7);
+    #[cfg(feature = "display")]
+    let epd_cs = Output::new(peripherals.GPIO10, Level::High, OutputConfig::default());
+    // `Spi` only implements `SpiBus`, not the `SpiDevice` the `ssd1680` driver
+    // requires, so we pair it with its own CS pin through `ExclusiveDevice`.
+    #[cfg(feature = "display")]
+    let epd_spi = embedded_hal_bus::spi::ExclusiveDevice::new_no_delay(epd_spi_bus, epd_cs)
+        .expect("Failed to attach e-paper chip-select pin");
+    #[cfg(feature = "display")]
+    let epd_dc = Output::new(peripherals.GPIO3, Level::Low, OutputConfig::default());
+    #[cfg(feature = "display")]
+    let epd_rst = Output::new(peripherals.GPIO2, Level::High, OutputConfig::default());
+    #[cfg(feature = "display")]
+    let epd_busy = Input::new(peripherals.GPIO1, InputConfig::default());
+    #[cfg(feature = "display")]
+    let mut epd = Ssd1680::new(epd_spi, epd_busy, epd_dc, epd_rst, &mut epd_delay)
+        .expect("Failed to initialize SSD1680 e-paper display");
+    #[cfg(feature = "display")]
+    let mut epd_display = Display2in13::bw();
+    #[cfg(feature = "display")]
+    epd_display.set_rotation(DisplayRotation::Rotate90);
+
     // Start WiFi in station mode for scanning
     wifi_controller.start().expect("Failed to start WiFi");
 
+    // Optional headless telemetry: connect to a configured AP, bring up a
+    // DHCP-configured embassy-net stack, and publish each scan record over
+    // MQTT alongside the UART JSON stream. Connecting is
+    // async-only, so we block on it once here rather than restructure the
+    // rest of `main` (which drives `wifi_controller` synchronously) around
+    // an executor.
+    #[cfg(feature = "network")]
+    wifi_controller
+        .set_config(&WifiConfig::Station(
+            StationConfig::default()
+                .with_ssid(WIFI_SSID)
+                .with_password(WIFI_PASSWORD.into()),
+        ))
+        .expect("Failed to configure WiFi station");
+    #[cfg(feature = "network")]
+    embassy_futures::block_on(wifi_controller.connect_async()).expect("Failed to connect to WiFi");
+
+    #[cfg(feature = "network")]
+    static NET_RESOURCES: static_cell::StaticCell<embassy_net::StackResources<4>> =
+        static_cell::StaticCell::new();
+    #[cfg(feature = "network")]
+    let (stack, net_runner) = embassy_net::new(
+        interfaces.station,
+        embassy_net::Config::dhcpv4(Default::default()),
+        NET_RESOURCES.init(embassy_net::StackResources::new()),
+        0x0123_4567_89ab_cdef,
+    );
+
+    // Network tasks run on their own interrupt-mode executor so they keep
+    // making progress while `main` stays synchronous for scanning.
+    #[cfg(feature = "network")]
+    static NET_EXECUTOR: static_cell::StaticCell<esp_rtos::embassy::InterruptExecutor<1>> =
+        static_cell::StaticCell::new();
+    #[cfg(feature = "network")]
+    let net_spawner = NET_EXECUTOR
+        .init(esp_rtos::embassy::InterruptExecutor::new(
+            sw_interrupt.software_interrupt1,
+        ))
+        .start(Priority::Priority1);
+    #[cfg(feature = "network")]
+    net_spawner
+        .spawn(net_runner_task(net_runner))
+        .expect("Failed to spawn network driver");
+    #[cfg(feature = "network")]
+    net_spawner
+        .spawn(network_task(stack))
+        .expect("Failed to spawn MQTT publisher");
+
+    // Optional promiscuous (monitor) mode: count unique transmitting MACs
+    // instead of proxying device presence off AP RSSI.
+    #[cfg(feature = "promiscuous")]
+    let mut sniffer = interfaces.sniffer;
+    #[cfg(feature = "promiscuous")]
+    sniffer.set_receive_cb(on_promiscuous_frame);
+    #[cfg(feature = "promiscuous")]
+    sniffer
+        .set_promiscuous_mode(true)
+        .expect("Failed to enable promiscuous mode");
+    #[cfg(feature = "promiscuous")]
+    let mut stations: FnvIndexMap<[u8; 6], Station, 32> = FnvIndexMap::new();
+
+    // Optional CSI capture: a much more sensitive multipath-motion signal
+    // than RSSI, tracked relative to an EMA baseline learned during quiet
+    // periods.
+    #[cfg(feature = "csi")]
+    wifi_controller
+        .set_csi(CsiConfig::default(), on_csi_frame)
+        .expect("Failed to configure CSI");
+    #[cfg(feature = "csi")]
+    let mut csi_baseline_energy: Option<f32> = None;
+
     let mut counter = 0u32;
     let mut last_scan = Instant::now();
+    let mut last_ble_scan = Instant::now();
 
-    // Baseline RSSI values for motion detection (track up to 10 APs)
-    let mut baseline_rssi: [i8; 10] = [-100; 10];
-    let mut baseline_ssid: [Option<heapless::String<32>>; 10] = [const { None }; 10];
+    // Per-AP sliding-window RSSI statistics for motion detection (track up
+    // to 10 APs)
+    let mut ap_stats: [Option<ApStats>; 10] = [const { None }; 10];
+
+    // Baseline RSSI for nearby BLE advertisers (track up to 8 devices)
+    let mut ble_baseline: [Option<BleDevice>; 8] = [None; 8];
+    let mut ble_values: heapless::Vec<([u8; 6], i8), 8> = heapless::Vec::new();
+    let mut ble_new_device = false;
 
     let _ = writeln!(uart0, "{{\"status\":\"WiFi Motion Sensor Started\"}}");
 
     loop {
         let now = Instant::now();
 
+        // Drain frames captured by the promiscuous-mode callback and retire
+        // any station that's gone quiet for longer than `STATION_EXPIRY`.
+        #[cfg(feature = "promiscuous")]
+        {
+            critical_section::with(|cs| {
+                let mut queue = SNIFFED_FRAMES.borrow_ref_mut(cs);
+                while let Some(frame) = queue.pop_front() {
+                    update_station(&mut stations, frame, now);
+                }
+            });
+
+            stations.retain(|_, station| now - station.last_seen < STATION_EXPIRY);
+        }
+
+        // Passive BLE scan: refresh the advertiser baseline and note whether
+        // a previously-unseen device just showed up (a strong presence
+        // signal compared to WiFi multipath alone).
+        if last_ble_scan.elapsed() >= BLE_SCAN_INTERVAL {
+            let adverts = ble_scan_advertisements(&mut ble_connector, BLE_SCAN_WINDOW);
+            ble_values.clear();
+            ble_new_device = false;
+
+            for (addr, rssi) in adverts.iter().copied() {
+                let mut baseline_idx = None;
+                for (bidx, dev) in ble_baseline.iter().enumerate() {
+                    if let Some(d) = dev {
+                        if d.addr == addr {
+                            baseline_idx = Some(bidx);
+                            break;
+                        }
+                    }
+                }
+
+                if baseline_idx.is_none() {
+                    for (bidx, slot) in ble_baseline.iter_mut().enumerate() {
+                        if slot.is_none() {
+                            *slot = Some(BleDevice {
+                                addr,
+                                rssi_ema: rssi,
+                            });
+                            baseline_idx = Some(bidx);
+                            ble_new_device = true;
+                            break;
+                        }
+                    }
+                }
+
+                if let Some(bidx) = baseline_idx {
+                    if let Some(dev) = ble_baseline[bidx].as_mut() {
+                        dev.rssi_ema = ((dev.rssi_ema as i16 * 90 + rssi as i16 * 10) / 100) as i8;
+                    }
+                }
+
+                let _ = ble_values.push((addr, rssi));
+            }
+
+            last_ble_scan = now;
+        }
+
         // Scan for WiFi networks every 1 second
         if last_scan.elapsed() >= Duration::from_millis(1000) {
             // Create scan config
@@ -74,67 +970,165 @@ fn main() -> ! {
             match wifi_controller.scan_with_config(scan_config) {
                 Ok(scan_results) => {
                     let mut motion_detected = false;
+                    let mut disturbed_aps = 0usize;
                     let mut rssi_values = heapless::Vec::<_, 10>::new();
 
                     // Process up to 10 strongest access points
-                    for (idx, ap) in scan_results.iter().take(10).enumerate() {
+                    for ap in scan_results.iter().take(10) {
                         let rssi = ap.signal_strength;
                         let ssid_str = ap.ssid.as_str();
-                        let ssid_owned: heapless::String<32> =
-                            heapless::String::try_from(ssid_str).unwrap_or_default();
-
-                        // Find matching baseline by SSID
-                        let mut baseline_idx = None;
-                        for (bidx, bssid) in baseline_ssid.iter().enumerate() {
-                            if let Some(bs) = bssid {
-                                if bs == &ssid_owned {
-                                    baseline_idx = Some(bidx);
-                                    break;
-                                }
-                            }
-                        }
 
-                        // If not found, add to baseline
-                        if baseline_idx.is_none() && idx < 10 {
-                            if baseline_ssid[idx].is_none() {
-                                baseline_ssid[idx] = Some(ssid_owned.clone());
-                                baseline_rssi[idx] = rssi;
-                                baseline_idx = Some(idx);
+                        let (disturbed, mean, sigma) = match find_ap_slot(&ap_stats, ssid_str) {
+                            ApSlot::Existing(sidx) => {
+                                let stats = ap_stats[sidx].as_mut().unwrap();
+                                // Disturbance check happens against the
+                                // window *before* this sample is folded in,
+                                // so a step change shows up as a disturbance
+                                // instead of immediately being absorbed into
+                                // the mean.
+                                let disturbed = stats.is_disturbed(rssi);
+                                stats.push(rssi);
+                                (disturbed, stats.mean, stats.sigma())
                             }
-                        }
-
-                        // Check for motion (RSSI change > 4 dBm)
-                        if let Some(bidx) = baseline_idx {
-                            let rssi_delta = (rssi - baseline_rssi[bidx]).abs();
-                            if rssi_delta > 4 && counter > 5 {
-                                motion_detected = true;
+                            ApSlot::Free(free_idx) => {
+                                // First time we've seen this SSID: open a
+                                // fresh window, too few samples to judge
+                                // disturbance.
+                                let ssid_owned: heapless::String<32> =
+                                    heapless::String::try_from(ssid_str).unwrap_or_default();
+                                let stats = ApStats::new(ssid_owned, rssi);
+                                let mean = stats.mean;
+                                ap_stats[free_idx] = Some(stats);
+                                (false, mean, 0.0)
                             }
+                            ApSlot::Full => (false, rssi as f32, 0.0),
+                        };
 
-                            // Update baseline with exponential moving average
-                            baseline_rssi[bidx] =
-                                ((baseline_rssi[bidx] as i16 * 90 + rssi as i16 * 10) / 100) as i8;
+                        if disturbed {
+                            disturbed_aps += 1;
                         }
 
                         // Collect RSSI data for output
-                        let _ = rssi_values.push((ssid_str, rssi, ap.channel));
+                        let _ =
+                            rssi_values.push((ssid_str, rssi, ap.channel, mean, sigma, disturbed));
+                    }
+
+                    if disturbed_aps >= AP_MIN_DISTURBED {
+                        motion_detected = true;
                     }
 
-                    // Send data via UART
-                    let _ = write!(uart0, "{{\"counter\":{},\"motion\":{},\"aps\":[",
+                    // CSI motion energy: flag motion when energy exceeds the
+                    // quiet-period baseline by a factor of `CSI_MOTION_K`;
+                    // only update the baseline itself while quiet, so it
+                    // never learns the disturbance as the new normal.
+                    #[cfg(feature = "csi")]
+                    let mut csi_energy: Option<f32> = None;
+                    #[cfg(feature = "csi")]
+                    let mut csi_motion = false;
+                    #[cfg(feature = "csi")]
+                    if let Some(energy) = csi_motion_energy() {
+                        csi_energy = Some(energy);
+                        match csi_baseline_energy {
+                            Some(baseline) if energy > baseline * CSI_MOTION_K => {
+                                csi_motion = true;
+                            }
+                            Some(baseline) => {
+                                csi_baseline_energy = Some(
+                                    baseline * (1.0 - CSI_BASELINE_ALPHA)
+                                        + energy * CSI_BASELINE_ALPHA,
+                                );
+                            }
+                            None => {
+                                csi_baseline_energy = Some(energy);
+                            }
+                        }
+                    }
+                    #[cfg(feature = "csi")]
+                    if csi_motion {
+                        motion_detected = true;
+                    }
+
+                    // Fuse WiFi disturbance, CSI energy and BLE presence into
+                    // a single confidence score: WiFi multipath alone
+                    // ("someone walked by") is weighted lower than a
+                    // brand-new BLE advertiser appearing ("a phone/wearable
+                    // entered the room").
+                    let wifi_confidence: f32 = if motion_detected { 0.6 } else { 0.0 };
+                    let ble_confidence: f32 = if ble_new_device { 0.4 } else { 0.0 };
+                    let presence = (wifi_confidence + ble_confidence).min(1.0);
+
+                    // Build the record once so it can go both to UART and,
+                    // when networking is enabled, to the MQTT publish queue.
+                    let mut line: heapless::String<512> = heapless::String::new();
+                    let _ = write!(
+                        line,
+                        "{{\"counter\":{},\"motion\":{},\"aps\":[",
                         counter,
                         if motion_detected { 1 } else { 0 }
                     );
 
-                    for (idx, (ssid, rssi, channel)) in rssi_values.iter().enumerate() {
+                    for (idx, (ssid, rssi, channel, mean, sigma, disturbed)) in
+                        rssi_values.iter().enumerate()
+                    {
+                        if idx > 0 {
+                            let _ = write!(line, ",");
+                        }
+                        let _ = write!(line, "{{\"ssid\":\"{}\",\"rssi\":{},\"ch\":{},\"mu\":{:.1},\"sigma\":{:.1},\"disturbed\":{}}}",
+                            ssid, rssi, channel, mean, sigma, disturbed
+                        );
+                    }
+
+                    let _ = write!(line, "],\"ble\":[");
+
+                    for (idx, (addr, rssi)) in ble_values.iter().enumerate() {
                         if idx > 0 {
-                            let _ = write!(uart0, ",");
+                            let _ = write!(line, ",");
                         }
-                        let _ = write!(uart0, "{{\"ssid\":\"{}\",\"rssi\":{},\"ch\":{}}}",
-                            ssid, rssi, channel
+                        let _ = write!(line, "{{\"addr\":\"{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}\",\"rssi\":{}}}",
+                            addr[0], addr[1], addr[2], addr[3], addr[4], addr[5], rssi
+                        );
+                    }
+
+                    let _ = write!(line, "],\"presence\":{:.2}", presence);
+
+                    #[cfg(feature = "csi")]
+                    if let Some(energy) = csi_energy {
+                        let _ = write!(line, ",\"csi_energy\":{:.1}", energy);
+                    }
+
+                    #[cfg(feature = "promiscuous")]
+                    {
+                        let _ = write!(line, ",\"devices\":{},\"macs\":[", stations.len());
+                        for (idx, (addr, station)) in stations.iter().enumerate() {
+                            if idx > 0 {
+                                let _ = write!(line, ",");
+                            }
+                            let _ = write!(line, "{{\"addr\":\"{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}\",\"rssi\":{}}}",
+                                addr[0], addr[1], addr[2], addr[3], addr[4], addr[5], station.rssi_ema
+                            );
+                        }
+                        let _ = write!(line, "]");
+                    }
+
+                    let _ = write!(line, "}}");
+
+                    #[cfg(feature = "network")]
+                    enqueue_telemetry(line.clone());
+
+                    #[cfg(feature = "display")]
+                    {
+                        render_display(
+                            &mut epd_display,
+                            motion_detected,
+                            presence,
+                            rssi_values.len(),
+                            &rssi_values,
                         );
+                        let _ = epd.update_bw_frame(epd_display.buffer());
+                        let _ = epd.display_frame(&mut epd_delay);
                     }
 
-                    let _ = writeln!(uart0, "]}}");
+                    let _ = writeln!(uart0, "{}", line);
 
                     counter = counter.wrapping_add(1);
                 }
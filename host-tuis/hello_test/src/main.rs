@@ -1,14 +1,20 @@
 use ratatui::{DefaultTerminal, Frame};
-use ratatui::layout::{Constraint, Direction, Layout};
-use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph, BarChart, Gauge};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::widgets::{Block, Borders, List, ListItem, Paragraph, Gauge, Sparkline};
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write as IoWrite};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use crossterm::event::{self, Event, KeyCode};
 
+// Number of RSSI samples kept per SSID for the history sparklines.
+const RSSI_HISTORY_LEN: usize = 120;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct AccessPoint {
     ssid: String,
@@ -23,56 +29,146 @@ struct Esp32Data {
     aps: Vec<AccessPoint>,
 }
 
+// A single recorded line, wrapped with a host-side timestamp so replay can
+// reproduce the original pacing of the session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedLine {
+    host_ts_ms: u128,
+    raw: String,
+}
+
 struct AppState {
     latest_data: Option<Esp32Data>,
     messages: Vec<String>,
     port_name: String,
+    history: HashMap<String, VecDeque<i8>>,
+    recording: Option<File>,
+    record_path: Option<String>,
 }
 
 fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
 
-    // List available serial ports
-    let ports = serialport::available_ports()?;
-
-    if ports.is_empty() {
-        eprintln!("No serial ports found!");
-        return Ok(());
-    }
+    let args: Vec<String> = std::env::args().collect();
+    let replay_path = args
+        .iter()
+        .position(|a| a == "--replay")
+        .and_then(|i| args.get(i + 1).cloned());
+
+    let port_name = match &replay_path {
+        Some(path) => format!("replay:{}", path),
+        None => {
+            // List available serial ports
+            let ports = serialport::available_ports()?;
+
+            if ports.is_empty() {
+                eprintln!("No serial ports found!");
+                return Ok(());
+            }
 
-    println!("Available serial ports:");
-    for (i, p) in ports.iter().enumerate() {
-        println!("  [{}] {}", i, p.port_name);
-    }
+            println!("Available serial ports:");
+            for (i, p) in ports.iter().enumerate() {
+                println!("  [{}] {}", i, p.port_name);
+            }
 
-    // Use first port or specified port
-    let port_name = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| ports[0].port_name.clone());
+            // Use first port or specified port
+            args.get(1)
+                .cloned()
+                .unwrap_or_else(|| ports[0].port_name.clone())
+        }
+    };
 
-    println!("Using port: {}", port_name);
+    println!("Using source: {}", port_name);
 
     let state = Arc::new(Mutex::new(AppState {
         latest_data: None,
         messages: Vec::new(),
         port_name: port_name.clone(),
+        history: HashMap::new(),
+        recording: None,
+        record_path: None,
     }));
 
-    // Spawn serial reader thread
     let state_clone = Arc::clone(&state);
-    thread::spawn(move || {
-        if let Err(e) = read_serial_port(&port_name, state_clone) {
-            eprintln!("Serial port error: {}", e);
+    match replay_path {
+        Some(path) => {
+            thread::spawn(move || {
+                if let Err(e) = replay_file(&path, state_clone) {
+                    eprintln!("Replay error: {}", e);
+                }
+            });
+        }
+        None => {
+            let live_port_name = port_name.clone();
+            thread::spawn(move || {
+                if let Err(e) = read_serial_port(&live_port_name, state_clone) {
+                    eprintln!("Serial port error: {}", e);
+                }
+            });
         }
-    });
+    }
 
-    // Give serial thread time to start
+    // Give reader thread time to start
     thread::sleep(Duration::from_millis(500));
 
     ratatui::run(|terminal| app(terminal, &state))?;
     Ok(())
 }
 
+fn current_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+// Parses a single line from the device (or a replayed capture) and folds it
+// into shared state: latest snapshot, event log, per-SSID RSSI history, and
+// the active recording file, if any.
+fn ingest_line(state: &Arc<Mutex<AppState>>, line: &str) {
+    let mut state = state.lock().unwrap();
+
+    if let Ok(data) = serde_json::from_str::<Esp32Data>(line) {
+        let motion_status = if data.motion > 0 { "MOTION!" } else { "still" };
+        state.messages.push(format!(
+            "Counter: {} | {} | {} APs",
+            data.counter,
+            motion_status,
+            data.aps.len()
+        ));
+
+        for ap in &data.aps {
+            let hist = state
+                .history
+                .entry(ap.ssid.clone())
+                .or_default();
+            hist.push_back(ap.rssi);
+            if hist.len() > RSSI_HISTORY_LEN {
+                hist.pop_front();
+            }
+        }
+
+        state.latest_data = Some(data);
+    } else {
+        state.messages.push(format!("Raw: {}", line));
+    }
+
+    // Keep only last 100 messages
+    if state.messages.len() > 100 {
+        state.messages.remove(0);
+    }
+
+    if let Some(file) = state.recording.as_mut() {
+        let record = RecordedLine {
+            host_ts_ms: current_millis(),
+            raw: line.to_string(),
+        };
+        if let Ok(json) = serde_json::to_string(&record) {
+            let _ = writeln!(file, "{}", json);
+        }
+    }
+}
+
 fn read_serial_port(port_name: &str, state: Arc<Mutex<AppState>>) -> color_eyre::Result<()> {
     let mut port = serialport::new(port_name, 115_200)
         .timeout(Duration::from_millis(100))
@@ -96,22 +192,7 @@ fn read_serial_port(port_name: &str, state: Arc<Mutex<AppState>>) -> color_eyre:
                         continue;
                     }
 
-                    let mut state = state.lock().unwrap();
-
-                    // Try to parse as JSON
-                    if let Ok(data) = serde_json::from_str::<Esp32Data>(&line) {
-                        let motion_status = if data.motion > 0 { "MOTION!" } else { "still" };
-                        state.messages.push(format!("Counter: {} | {} | {} APs",
-                            data.counter, motion_status, data.aps.len()));
-                        state.latest_data = Some(data.clone());
-                    } else {
-                        state.messages.push(format!("Raw: {}", line));
-                    }
-
-                    // Keep only last 100 messages
-                    if state.messages.len() > 100 {
-                        state.messages.remove(0);
-                    }
+                    ingest_line(&state, &line);
                 }
             }
             _ => {
@@ -121,14 +202,82 @@ fn read_serial_port(port_name: &str, state: Arc<Mutex<AppState>>) -> color_eyre:
     }
 }
 
+// Replays a previously recorded NDJSON capture through the same parse path
+// as the serial reader, reproducing the original inter-line pacing (gaps
+// capped at 2 s) so detection thresholds can be tuned offline. Falls back to
+// a fixed pace for plain (unwrapped) raw-line captures.
+fn replay_file(path: &str, state: Arc<Mutex<AppState>>) -> color_eyre::Result<()> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+
+    let mut prev_ts: Option<u128> = None;
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let raw = match serde_json::from_str::<RecordedLine>(&line) {
+            Ok(record) => {
+                if let Some(prev) = prev_ts {
+                    let delta = record.host_ts_ms.saturating_sub(prev).min(2_000) as u64;
+                    thread::sleep(Duration::from_millis(delta));
+                }
+                prev_ts = Some(record.host_ts_ms);
+                record.raw
+            }
+            Err(_) => {
+                thread::sleep(Duration::from_millis(200));
+                line
+            }
+        };
+
+        ingest_line(&state, &raw);
+    }
+
+    {
+        let mut state = state.lock().unwrap();
+        state.messages.push("Replay finished".to_string());
+    }
+
+    Ok(())
+}
+
+// Starts or stops recording the raw line stream to a timestamped NDJSON
+// file in the current directory.
+fn toggle_recording(state: &Arc<Mutex<AppState>>) {
+    let mut state = state.lock().unwrap();
+
+    if state.recording.take().is_some() {
+        let path = state.record_path.take().unwrap_or_default();
+        state.messages.push(format!("Stopped recording ({})", path));
+        return;
+    }
+
+    let path = format!("capture-{}.ndjson", current_millis());
+    match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(file) => {
+            state.messages.push(format!("Recording to {}", path));
+            state.record_path = Some(path);
+            state.recording = Some(file);
+        }
+        Err(e) => {
+            state.messages.push(format!("Failed to start recording: {}", e));
+        }
+    }
+}
+
 fn app(terminal: &mut DefaultTerminal, state: &Arc<Mutex<AppState>>) -> std::io::Result<()> {
     loop {
         terminal.draw(|frame| render(frame, state))?;
 
         if event::poll(Duration::from_millis(100))? {
             if let Event::Key(key) = event::read()? {
-                if key.code == KeyCode::Char('q') || key.code == KeyCode::Esc {
-                    break Ok(());
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => break Ok(()),
+                    KeyCode::Char('r') => toggle_recording(state),
+                    _ => {}
                 }
             }
         }
@@ -143,7 +292,8 @@ fn render(frame: &mut Frame, state: &Arc<Mutex<AppState>>) {
         .constraints([
             Constraint::Length(3),    // Header
             Constraint::Length(3),    // Motion indicator
-            Constraint::Min(10),      // RSSI bars
+            Constraint::Min(8),       // RSSI bars
+            Constraint::Length(9),    // RSSI history sparklines
             Constraint::Length(10),   // Messages
         ])
         .split(frame.area());
@@ -153,9 +303,11 @@ fn render(frame: &mut Frame, state: &Arc<Mutex<AppState>>) {
         .map(|d| if d.motion > 0 { "⚠ MOTION DETECTED" } else { "• Still" })
         .unwrap_or("• Waiting...");
 
+    let recording_status = if state.recording.is_some() { " | ● REC" } else { "" };
+
     let header = Paragraph::new(format!(
-        "ESP32-C3 WiFi Motion Sensor | Port: {} | {} | Press 'q' to quit",
-        state.port_name, motion_status
+        "ESP32-C3 WiFi Motion Sensor | Port: {} | {}{} | 'q' quit, 'r' record",
+        state.port_name, motion_status, recording_status
     ))
     .block(Block::default().borders(Borders::ALL).title("Status"))
     .style(Style::default().fg(Color::Cyan));
@@ -221,6 +373,8 @@ fn render(frame: &mut Frame, state: &Arc<Mutex<AppState>>) {
         frame.render_widget(waiting, chunks[2]);
     }
 
+    render_rssi_history(frame, chunks[3], &state);
+
     // Message log
     let messages: Vec<ListItem> = state
         .messages
@@ -233,5 +387,48 @@ fn render(frame: &mut Frame, state: &Arc<Mutex<AppState>>) {
     let messages_widget = List::new(messages)
         .block(Block::default().borders(Borders::ALL).title("Event Log"))
         .style(Style::default().fg(Color::Gray));
-    frame.render_widget(messages_widget, chunks[3]);
+    frame.render_widget(messages_widget, chunks[4]);
+}
+
+// Renders a stacked sparkline for each of the first three SSIDs in the
+// latest snapshot so trends (and the disturbance that triggered motion) are
+// visible over time, not just the latest snapshot.
+fn render_rssi_history(frame: &mut Frame, area: Rect, state: &AppState) {
+    if let Some(ref data) = state.latest_data {
+        let ssids: Vec<String> = data.aps.iter().take(3).map(|ap| ap.ssid.clone()).collect();
+
+        if ssids.is_empty() {
+            let waiting = Paragraph::new("No APs tracked yet")
+                .block(Block::default().borders(Borders::ALL).title("RSSI History"));
+            frame.render_widget(waiting, area);
+            return;
+        }
+
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![Constraint::Length(3); ssids.len()])
+            .split(area);
+
+        for (row, ssid) in rows.iter().zip(ssids.iter()) {
+            let samples: Vec<u64> = state
+                .history
+                .get(ssid)
+                .map(|hist| {
+                    hist.iter()
+                        .map(|&rssi| (rssi as i32 + 100).max(0) as u64)
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            let sparkline = Sparkline::default()
+                .block(Block::default().borders(Borders::ALL).title(ssid.clone()))
+                .data(&samples)
+                .style(Style::default().fg(Color::Cyan));
+            frame.render_widget(sparkline, *row);
+        }
+    } else {
+        let waiting = Paragraph::new("Waiting for RSSI history...")
+            .block(Block::default().borders(Borders::ALL).title("RSSI History"));
+        frame.render_widget(waiting, area);
+    }
 }